@@ -1,16 +1,24 @@
 //! Logger Filters
 
-use std::marker::PhantomData;
-use std::time::Instant;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures::Future;
-use http::StatusCode;
+use futures::{Future, Stream};
+use futures::sync::mpsc::{self, Receiver, Sender, UnboundedSender};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
+use http::header::{CONTENT_LENGTH, HOST, REFERER, USER_AGENT};
+use time::{self, Tm};
+use tokio::timer::Interval;
 
 use ::filter::{Filter, FilterClone, One};
 use ::never::Never;
 use ::reject::{CombineRejection, Reject};
-use ::reply::{Reply, ReplySealed};
-use ::route;
+use ::reply::{Reply, ReplySealed, Response};
+use ::route::{self, Route};
 
 use self::internal::Logged;
 
@@ -33,29 +41,639 @@ use self::internal::Logged;
 /// ```
 pub fn log(name: &'static str) -> Log<impl Fn(Info) + Copy> {
     let func = move |info: Info| {
-        route::with(|route| {
-            // TODO:
-            // - remote_addr
-            // - response content length
-            // - date
-            info!(
-                target: name,
-                "\"{} {} {:?}\" {} {:?}",
-                route.method(),
-                route.full_path(),
-                route.version(),
-                info.status.as_u16(),
-                info.start.elapsed(),
-            );
-        });
+        info!(
+            target: name,
+            "\"{} {} {:?}\" {} {:?}",
+            info.method(),
+            info.path(),
+            info.version(),
+            info.status().as_u16(),
+            info.elapsed(),
+        );
+    };
+    Log {
+        func,
+    }
+}
+
+/// Create a `Log` filter that calls the provided function with each
+/// request's [`Info`](Info), instead of being locked into a single,
+/// hardcoded log line.
+///
+/// # Example
+///
+/// ```
+/// use warp::Filter;
+///
+/// let log = warp::log::custom(|info| {
+///     eprintln!(
+///         "{} {} => {} in {:?}",
+///         info.method(),
+///         info.path(),
+///         info.status(),
+///         info.elapsed(),
+///     );
+/// });
+/// let route = log.decorate(
+///     warp::any().map(warp::reply)
+/// );
+/// ```
+pub fn custom<F>(func: F) -> Log<F>
+where
+    F: Fn(Info),
+{
+    Log {
+        func,
+    }
+}
+
+/// Create a `Log` filter that writes access lines in the
+/// [NCSA Common Log Format](https://en.wikipedia.org/wiki/Common_Log_Format):
+///
+/// ```text
+/// 127.0.0.1 - - [19/Jul/2019:12:34:56 +0000] "GET /path HTTP/1.1" 200 1234
+/// ```
+pub fn common() -> Log<impl Fn(Info) + Copy> {
+    let func = |info: Info| {
+        info!("{}", common_log_line(&info));
+    };
+    Log {
+        func,
+    }
+}
+
+/// Create a `Log` filter that writes access lines in the
+/// [NCSA Combined Log Format](https://httpd.apache.org/docs/current/logs.html#combined),
+/// the Common Log Format with the `Referer` and `User-Agent` headers appended:
+///
+/// ```text
+/// 127.0.0.1 - - [19/Jul/2019:12:34:56 +0000] "GET /path HTTP/1.1" 200 1234 "https://example.com/" "my-client/1.0"
+/// ```
+pub fn combined() -> Log<impl Fn(Info) + Copy> {
+    let func = |info: Info| {
+        info!(
+            "{} \"{}\" \"{}\"",
+            common_log_line(&info),
+            info.referer().unwrap_or("-"),
+            info.user_agent().unwrap_or("-"),
+        );
     };
     Log {
         func,
     }
 }
 
-// TODO:
-// pub fn custom(impl Fn(Info)) -> Log
+/// Format `info` as an NCSA Common Log Format access line (minus the
+/// trailing newline), shared by [`common`](common), [`combined`](combined),
+/// and [`to_files`](to_files).
+fn common_log_line(info: &Info) -> String {
+    format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {}",
+        info.remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "-".to_string()),
+        info.timestamp()
+            .strftime("%d/%b/%Y:%H:%M:%S %z")
+            .expect("valid strftime format"),
+        info.method(),
+        info.path(),
+        info.version(),
+        info.status().as_u16(),
+        info.bytes()
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| "-".to_string()),
+    )
+}
+
+/// A single field that can be included in a [`json`](json) log record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// The request's HTTP method.
+    Method,
+    /// The request's full path.
+    Path,
+    /// The request's raw query string.
+    Query,
+    /// The request's HTTP version.
+    Version,
+    /// The response's status code.
+    Status,
+    /// How long the request took to process, in milliseconds.
+    DurationMs,
+    /// The remote address of the connection.
+    RemoteAddr,
+    /// The response's content length in bytes.
+    Bytes,
+}
+
+impl Field {
+    fn all() -> Vec<Field> {
+        vec![
+            Field::Method,
+            Field::Path,
+            Field::Query,
+            Field::Version,
+            Field::Status,
+            Field::DurationMs,
+            Field::RemoteAddr,
+            Field::Bytes,
+        ]
+    }
+}
+
+/// A builder for a `Log` filter that emits one JSON object per request.
+///
+/// Created by [`json`](json).
+#[derive(Clone, Debug)]
+pub struct Json {
+    fields: Option<Vec<Field>>,
+    headers: Vec<HeaderName>,
+}
+
+/// Create a builder for a `Log` filter that emits one JSON object per
+/// request, instead of the human-readable, colored line suited to a
+/// terminal.
+///
+/// The output is deliberately color-free and quote-escaped, so it's safe
+/// to append to files and ingest with log shippers.
+///
+/// By default every [`Field`](Field) is included. Call
+/// [`field`](Json::field) to restrict the set (the first call switches
+/// from "all fields" to just the ones added), and [`header`](Json::header)
+/// to additionally include specific request headers, so log volume can be
+/// kept under control.
+///
+/// # Example
+///
+/// ```
+/// use warp::log::Field;
+///
+/// let log = warp::log::json()
+///     .field(Field::Method)
+///     .field(Field::Path)
+///     .field(Field::Status)
+///     .header("x-request-id")
+///     .build();
+/// ```
+pub fn json() -> Json {
+    Json {
+        fields: None,
+        headers: Vec::new(),
+    }
+}
+
+impl Json {
+    /// Include `field` in the emitted JSON object.
+    ///
+    /// The first call to this method restricts the output to just the
+    /// fields added through it, instead of the default full set.
+    pub fn field(mut self, field: Field) -> Self {
+        self.fields.get_or_insert_with(Vec::new).push(field);
+        self
+    }
+
+    /// Additionally include the named header in the emitted JSON object,
+    /// preferring a response header over a request header of the same
+    /// name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn header(mut self, name: impl AsRef<str>) -> Self {
+        let name = HeaderName::from_bytes(name.as_ref().as_bytes())
+            .expect("invalid header name");
+        self.headers.push(name);
+        self
+    }
+
+    /// Build the configured `Log` filter.
+    pub fn build(self) -> Log<impl Fn(Info) + Clone> {
+        let fields = self.fields.unwrap_or_else(Field::all);
+        let headers = self.headers;
+        let func = move |info: Info| {
+            info!("{}", json_log_line(&info, &fields, &headers));
+        };
+        Log {
+            func,
+        }
+    }
+}
+
+fn json_log_line(info: &Info, fields: &[Field], headers: &[HeaderName]) -> String {
+    let mut entries = Vec::with_capacity(fields.len() + headers.len());
+
+    for field in fields {
+        let value = match *field {
+            Field::Method => json_string(info.method().as_str()),
+            Field::Path => json_string(info.path()),
+            Field::Query => info.query().map(json_string).unwrap_or_else(|| "null".to_string()),
+            Field::Version => json_string(&format!("{:?}", info.version())),
+            Field::Status => info.status().as_u16().to_string(),
+            Field::DurationMs => info.elapsed().as_millis().to_string(),
+            Field::RemoteAddr => info.remote_addr()
+                .map(|addr| json_string(&addr.to_string()))
+                .unwrap_or_else(|| "null".to_string()),
+            Field::Bytes => info.bytes().map(|n| n.to_string()).unwrap_or_else(|| "null".to_string()),
+        };
+        entries.push(format!("{}:{}", json_string(field_name(*field)), value));
+    }
+
+    for name in headers {
+        // A header set by the handler (e.g. a response `content-type` or
+        // `etag`) takes precedence over a same-named request header.
+        let value = info.response_headers()
+            .get(name)
+            .or_else(|| info.request_headers().get(name))
+            .and_then(|value| value.to_str().ok())
+            .map(json_string)
+            .unwrap_or_else(|| "null".to_string());
+        entries.push(format!("{}:{}", json_string(name.as_str()), value));
+    }
+
+    format!("{{{}}}", entries.join(","))
+}
+
+fn field_name(field: Field) -> &'static str {
+    match field {
+        Field::Method => "method",
+        Field::Path => "path",
+        Field::Query => "query",
+        Field::Version => "version",
+        Field::Status => "status",
+        Field::DurationMs => "duration_ms",
+        Field::RemoteAddr => "remote_addr",
+        Field::Bytes => "bytes",
+    }
+}
+
+/// Render `value` as a double-quoted, escaped JSON string.
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// A destination that completed request records can be dispatched to,
+/// instead of going through the `log` crate facade.
+pub trait Sink: Send + Sync + 'static {
+    /// Record a completed request.
+    fn record(&self, info: &Info);
+}
+
+/// Create a `Log` filter that hands each request's [`Info`](Info) to a
+/// [`Sink`](Sink).
+pub fn sink<S: Sink>(sink: S) -> Log<impl Fn(Info) + Clone> {
+    let sink = Arc::new(sink);
+    let func = move |info: Info| sink.record(&info);
+    Log {
+        func,
+    }
+}
+
+/// Write access and error logs to the given files.
+///
+/// Responses with a 1xx, 2xx, or 3xx status are appended to `access_path`;
+/// responses with a 4xx or 5xx status are appended to `error_path`. Both
+/// files are opened for appending, wrapped in a `BufWriter`, and written to
+/// by background tasks fed over unbounded channels, so that writing a log
+/// line never blocks the request-handling future — the hot path only
+/// formats the line and pushes the resulting `String`.
+///
+/// # Example
+///
+/// ```
+/// use warp::Filter;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let dir = std::env::temp_dir();
+/// let log = warp::log::to_files(dir.join("access.log"), dir.join("error.log"))?;
+/// let route = log.decorate(
+///     warp::any().map(warp::reply)
+/// );
+/// # let _ = route;
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_files<A, E>(access_path: A, error_path: E) -> io::Result<Log<impl Fn(Info) + Clone>>
+where
+    A: AsRef<Path>,
+    E: AsRef<Path>,
+{
+    let access = Arc::new(FileWriter::new(access_path)?);
+    let error = Arc::new(FileWriter::new(error_path)?);
+
+    Ok(self::sink(FileSink {
+        access,
+        error,
+    }))
+}
+
+#[derive(Clone)]
+struct FileSink {
+    access: Arc<FileWriter>,
+    error: Arc<FileWriter>,
+}
+
+impl Sink for FileSink {
+    fn record(&self, info: &Info) {
+        let line = common_log_line(info);
+        let writer = if is_error_status(info.status()) {
+            &self.error
+        } else {
+            &self.access
+        };
+        writer.send(line);
+    }
+}
+
+/// Whether a response's status belongs in the error log rather than the
+/// access log: a 4xx or 5xx.
+fn is_error_status(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+enum WriterEvent {
+    Line(String),
+    Flush,
+}
+
+/// Flush the buffered writer on this interval, independent of how often
+/// lines arrive.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A background file writer, fed over an unbounded channel.
+///
+/// `to_files` is meant to be called the same way as every other
+/// `warp::log::*` constructor: before `warp::serve(...).run(...)` starts
+/// the Tokio runtime. So the writer task can't be spawned eagerly —
+/// `tokio::spawn` panics without a runtime already driving it. Instead,
+/// the task is spawned the first time a line is actually sent, which can
+/// only happen once a request is being handled, i.e. once the runtime is
+/// definitely running.
+struct FileWriter {
+    tx: UnboundedSender<String>,
+    task: Mutex<Option<Box<Future<Item = (), Error = ()> + Send>>>,
+}
+
+impl FileWriter {
+    fn new<P: AsRef<Path>>(path: P) -> io::Result<FileWriter> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+
+        let (tx, rx) = mpsc::unbounded::<String>();
+        let lines = rx.map(WriterEvent::Line);
+        let ticks = Interval::new_interval(FLUSH_INTERVAL)
+            .map(|_| WriterEvent::Flush)
+            .map_err(|_| ());
+
+        let task = lines.select(ticks).for_each(move |event| {
+            match event {
+                WriterEvent::Line(line) => {
+                    let _ = writeln!(writer, "{}", line);
+                }
+                WriterEvent::Flush => {
+                    let _ = writer.flush();
+                }
+            }
+            Ok(())
+        });
+
+        Ok(FileWriter {
+            tx,
+            task: Mutex::new(Some(Box::new(task))),
+        })
+    }
+
+    fn send(&self, line: String) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            ::tokio::spawn(task);
+        }
+        let _ = self.tx.unbounded_send(line);
+    }
+}
+
+/// Create a builder for a `Log` filter that broadcasts each request's
+/// record over a live channel, plus a [`LogSubscriber`](LogSubscriber)
+/// handle for streaming those records back out, e.g. over SSE or a
+/// WebSocket for an admin dashboard.
+///
+/// Each subscriber gets its own cloned copy of every record produced
+/// while it is subscribed, as long as its channel (sized by `capacity`)
+/// has room; a record that finds a subscriber's channel full is dropped
+/// for that subscriber. When there are no subscribers, the record is
+/// dropped without ever being serialized, so the feature costs nothing
+/// when nobody is listening.
+///
+/// By default a [`Record`](Record) carries no request headers at all.
+/// Records are handed to every subscriber, which may stream them out of
+/// the process, so headers like `Authorization` or `Cookie` must not
+/// be included just because they happened to be on the request. Call
+/// [`header`](Broadcast::header) to explicitly opt specific headers in,
+/// the same way [`json`](json) requires.
+///
+/// # Example
+///
+/// ```
+/// use warp::Filter;
+///
+/// let (log, subscriber) = warp::log::broadcast(16)
+///     .header("x-request-id")
+///     .build();
+/// let route = log.decorate(
+///     warp::any().map(warp::reply)
+/// );
+/// let records = subscriber.subscribe();
+/// # let _ = (route, records);
+/// ```
+pub fn broadcast(capacity: usize) -> Broadcast {
+    Broadcast {
+        capacity,
+        headers: Vec::new(),
+    }
+}
+
+/// A builder for a [`broadcast`](broadcast) filter.
+#[derive(Clone, Debug)]
+pub struct Broadcast {
+    capacity: usize,
+    headers: Vec<HeaderName>,
+}
+
+impl Broadcast {
+    /// Additionally include the named header in each broadcast
+    /// [`Record`](Record), preferring a response header over a request
+    /// header of the same name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn header(mut self, name: impl AsRef<str>) -> Self {
+        let name = HeaderName::from_bytes(name.as_ref().as_bytes())
+            .expect("invalid header name");
+        self.headers.push(name);
+        self
+    }
+
+    /// Build the configured `Log` filter and its `LogSubscriber` handle.
+    pub fn build(self) -> (Log<impl Fn(Info) + Clone>, LogSubscriber) {
+        let sink = BroadcastSink {
+            hub: Arc::new(Mutex::new(Vec::new())),
+            capacity: self.capacity,
+            headers: Arc::new(self.headers),
+        };
+        let log = self::sink(sink.clone());
+        (log, LogSubscriber { sink })
+    }
+}
+
+/// A handle for subscribing to the live stream of records produced by a
+/// [`broadcast`](broadcast) filter.
+#[derive(Clone)]
+pub struct LogSubscriber {
+    sink: BroadcastSink,
+}
+
+impl LogSubscriber {
+    /// Subscribe to the stream of records.
+    ///
+    /// Each call returns an independent `Stream`, fed its own clone of
+    /// every record produced after this call; a record produced while
+    /// this stream's channel is full is dropped for this stream.
+    pub fn subscribe(&self) -> Receiver<Record> {
+        let (tx, rx) = mpsc::channel(self.sink.capacity);
+        self.sink.hub.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+#[derive(Clone)]
+struct BroadcastSink {
+    hub: Arc<Mutex<Vec<Sender<Record>>>>,
+    capacity: usize,
+    headers: Arc<Vec<HeaderName>>,
+}
+
+impl Sink for BroadcastSink {
+    fn record(&self, info: &Info) {
+        let mut senders = self.hub.lock().unwrap();
+        // `Record::from_info(info, ..)` is deferred behind this closure so
+        // that `publish` can skip it entirely when nobody is subscribed —
+        // the whole point of the feature costing nothing when unused.
+        publish(&mut senders, || Record::from_info(info, &self.headers));
+    }
+}
+
+/// Fan a record out to every sender, dropping ones that have disconnected.
+///
+/// `make_record` is only called if `senders` is non-empty, so that when
+/// there are no subscribers the record is never built in the first place.
+fn publish<F>(senders: &mut Vec<Sender<Record>>, make_record: F)
+where
+    F: FnOnce() -> Record,
+{
+    if senders.is_empty() {
+        return;
+    }
+    let record = make_record();
+    let mut kept = Vec::with_capacity(senders.len());
+    for mut tx in senders.drain(..) {
+        match tx.try_send(record.clone()) {
+            Ok(()) => kept.push(tx),
+            Err(ref err) if err.is_full() => kept.push(tx),
+            Err(_) => {}
+        }
+    }
+    *senders = kept;
+}
+
+/// An owned, cloneable snapshot of a completed request, as produced by
+/// [`broadcast`](broadcast).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    method: Method,
+    path: String,
+    version: Version,
+    status: StatusCode,
+    elapsed: Duration,
+    remote_addr: Option<SocketAddr>,
+    headers: HeaderMap<HeaderValue>,
+}
+
+impl Record {
+    /// The request's HTTP method.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The request's full path.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The request's HTTP version.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The response's status code.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// How long the request took to process.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The remote address of the connection, if known.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The headers explicitly opted in to via [`Broadcast::header`](Broadcast::header).
+    pub fn headers(&self) -> &HeaderMap<HeaderValue> {
+        &self.headers
+    }
+}
+
+impl Record {
+    /// Build a `Record` from `info`, including only the headers named in
+    /// `allowed` (preferring a response header over a request header of
+    /// the same name), instead of cloning the full raw `HeaderMap`.
+    fn from_info(info: &Info, allowed: &[HeaderName]) -> Record {
+        let mut headers = HeaderMap::with_capacity(allowed.len());
+        for name in allowed {
+            let value = info.response_headers()
+                .get(name)
+                .or_else(|| info.request_headers().get(name));
+            if let Some(value) = value {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+
+        Record {
+            method: info.method().clone(),
+            path: info.path().to_string(),
+            version: info.version(),
+            status: info.status(),
+            elapsed: info.elapsed(),
+            remote_addr: info.remote_addr(),
+            headers,
+        }
+    }
+}
 
 /// Decorates a [`Filter`](::Filter) to log requests and responses.
 #[derive(Clone, Copy, Debug)]
@@ -66,11 +684,93 @@ pub struct Log<F> {
 /// Information about the request/response that can be used to prepare log lines.
 #[allow(missing_debug_implementations)]
 pub struct Info<'a> {
+    route: &'a Route,
     start: Instant,
+    timestamp: Tm,
     status: StatusCode,
-    // This struct will eventually hold a `&'a Route` and `&'a Response`,
-    // so use a marker so there can be a lifetime in the struct definition.
-    _marker: PhantomData<&'a ()>,
+    bytes: Option<u64>,
+    response_headers: &'a HeaderMap<HeaderValue>,
+}
+
+impl<'a> Info<'a> {
+    /// The request's HTTP method.
+    pub fn method(&self) -> &Method {
+        self.route.method()
+    }
+
+    /// The request's full path.
+    pub fn path(&self) -> &str {
+        self.route.full_path()
+    }
+
+    /// The request's HTTP version.
+    pub fn version(&self) -> Version {
+        self.route.version()
+    }
+
+    /// The request's raw query string, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.route.raw_query()
+    }
+
+    /// The response's status code.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The time it took for warp to process the request and produce the response.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// The wall-clock time at which the request was received.
+    pub fn timestamp(&self) -> Tm {
+        self.timestamp
+    }
+
+    /// The response's content length in bytes, if known.
+    pub fn bytes(&self) -> Option<u64> {
+        self.bytes
+    }
+
+    /// The remote address of the connection, if known.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.route.remote_addr()
+    }
+
+    /// Value of the `Host` request header.
+    pub fn host(&self) -> Option<&str> {
+        self.route
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// Value of the `User-Agent` request header.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.route
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// Value of the `Referer` request header.
+    pub fn referer(&self) -> Option<&str> {
+        self.route
+            .headers()
+            .get(REFERER)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// All of the request's headers.
+    pub fn request_headers(&self) -> &HeaderMap<HeaderValue> {
+        self.route.headers()
+    }
+
+    /// All of the response's headers.
+    pub fn response_headers(&self) -> &HeaderMap<HeaderValue> {
+        self.response_headers
+    }
 }
 
 impl<FN> Log<FN>
@@ -91,27 +791,39 @@ where
         ::filters::any::any()
             .and_then(move || {
                 let start = Instant::now();
+                let timestamp = time::now();
                 let func = func.clone();
                 inner
                     .filter()
                     .then(move |result| {
-                        let (result, status) = match result {
-                            Ok(rep) => {
-                                let resp = rep.into_response();
-                                let status = resp.status();
-                                (Ok(Logged(resp)), status)
+                        // Normalize both outcomes to a `Response` up front, so the
+                        // `(StatusCode, Option<u64>, &HeaderMap)` used to build `Info`
+                        // is computed exactly once, from exactly one place, instead of
+                        // each arm separately constructing its own `Info`.
+                        let result: Result<Response, F::Error> = result.map(Reply::into_response);
+                        let empty_headers = HeaderMap::new();
+                        let (status, bytes, response_headers) = match &result {
+                            Ok(resp) => {
+                                let bytes = resp
+                                    .headers()
+                                    .get(CONTENT_LENGTH)
+                                    .and_then(|value| value.to_str().ok())
+                                    .and_then(|value| value.parse().ok());
+                                (resp.status(), bytes, resp.headers())
                             },
-                            Err(reject) => {
-                                let status = reject.status();
-                                (Err(reject), status)
-                            }
+                            Err(reject) => (reject.status(), None, &empty_headers),
                         };
-                        func(Info {
-                            start,
-                            status,
-                            _marker: PhantomData,
+                        route::with(|route| {
+                            func(Info {
+                                route,
+                                start,
+                                timestamp,
+                                status,
+                                bytes,
+                                response_headers,
+                            });
                         });
-                        result
+                        result.map(Logged)
                     })
             })
     }
@@ -129,4 +841,109 @@ mod internal {
             self.0
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_error_status_splits_4xx_5xx_from_everything_else() {
+        assert!(!is_error_status(StatusCode::OK));
+        assert!(!is_error_status(StatusCode::from_u16(304).unwrap()));
+        assert!(is_error_status(StatusCode::NOT_FOUND));
+        assert!(is_error_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[test]
+    fn json_string_escapes_control_and_special_characters() {
+        assert_eq!(json_string("hello"), "\"hello\"");
+        assert_eq!(json_string("a\"b"), "\"a\\\"b\"");
+        assert_eq!(json_string("a\\b"), "\"a\\\\b\"");
+        assert_eq!(json_string("a\nb"), "\"a\\nb\"");
+        assert_eq!(json_string("a\tb"), "\"a\\tb\"");
+    }
+
+    #[test]
+    fn json_builder_defaults_to_every_field_and_no_headers() {
+        let builder = json();
+        assert!(builder.fields.is_none());
+        assert!(builder.headers.is_empty());
+    }
+
+    #[test]
+    fn json_builder_field_restricts_to_just_the_added_fields() {
+        let builder = json().field(Field::Method).field(Field::Status);
+        assert_eq!(builder.fields, Some(vec![Field::Method, Field::Status]));
+    }
+
+    #[test]
+    fn json_builder_header_parses_the_name() {
+        let builder = json().header("x-request-id");
+        assert_eq!(builder.headers, vec![HeaderName::from_static("x-request-id")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn json_builder_header_panics_on_invalid_name() {
+        json().header("not a valid header name");
+    }
+
+    #[test]
+    fn broadcast_builder_defaults_to_no_headers() {
+        let builder = broadcast(16);
+        assert!(builder.headers.is_empty());
+    }
+
+    #[test]
+    fn broadcast_builder_header_parses_the_name() {
+        let builder = broadcast(16).header("x-request-id");
+        assert_eq!(builder.headers, vec![HeaderName::from_static("x-request-id")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn broadcast_builder_header_panics_on_invalid_name() {
+        broadcast(16).header("not a valid header name");
+    }
+
+    fn sample_record() -> Record {
+        Record {
+            method: Method::GET,
+            path: "/".to_string(),
+            version: Version::HTTP_11,
+            status: StatusCode::OK,
+            elapsed: Duration::from_millis(1),
+            remote_addr: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    #[test]
+    fn publish_skips_building_a_record_when_there_are_no_subscribers() {
+        let mut senders: Vec<Sender<Record>> = Vec::new();
+        publish(&mut senders, || {
+            unreachable!("make_record must not run with no subscribers")
+        });
+    }
+
+    #[test]
+    fn publish_fans_the_same_record_out_to_every_subscriber() {
+        let (tx1, rx1) = mpsc::channel(1);
+        let (tx2, rx2) = mpsc::channel(1);
+        let mut senders = vec![tx1, tx2];
+        publish(&mut senders, sample_record);
+        assert_eq!(senders.len(), 2);
+        assert_eq!(rx1.wait().next().unwrap().unwrap(), sample_record());
+        assert_eq!(rx2.wait().next().unwrap().unwrap(), sample_record());
+    }
+
+    #[test]
+    fn publish_drops_a_disconnected_subscriber() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let mut senders = vec![tx];
+        publish(&mut senders, sample_record);
+        assert!(senders.is_empty());
+    }
 }
\ No newline at end of file